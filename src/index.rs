@@ -0,0 +1,516 @@
+// Fixed-layout binary index for `PersonGraph` + `HeritageMap`, so large
+// datasets don't need to be reparsed from CSV on every invocation. Loosely
+// modeled on Mercurial's dirstate-v2 on-disk format: a small header with
+// counts, a contiguous node-record array (person id, its FatherID/MotherID/
+// SpouseID, name offset/len into a trailing string table, and offset/len
+// into a trailing adjacency array), and an adjacency array of `(neighbor
+// node index, Relationship tag)` pairs.
+//
+// Layout: [header][node records][adjacency records][name string table]
+//
+// All integers are little-endian. Node indices here match `NodeIndex<u32>`'s
+// `.index()`, i.e. nodes are numbered contiguously from 0. `FatherID`/
+// `MotherID`/`SpouseID` are serialized directly rather than inferred from
+// adjacency, since `PersonGraph` is undirected: a `Father`/`Mother`-tagged
+// edge shows up in both the child's and the parent's adjacency list, and
+// the edge alone can't tell which side is which (see `ancestor_depths` in
+// main.rs for the same caveat).
+
+use std::convert::TryInto;
+use std::error::Error;
+use std::fs;
+use std::fs::File;
+
+use memmap2::Mmap;
+
+use petgraph::graph::NodeIndex;
+use petgraph::visit::EdgeRef;
+
+use crate::{Heritage, HeritageMap, Person, PersonGraph, Relationship};
+
+const MAGIC: &[u8; 4] = b"HPIX";
+// Bump whenever the node record layout changes. Version 1 used a 20-byte
+// record with no FatherID/MotherID/SpouseID fields; version 2 is the current
+// 32-byte record.
+const VERSION: u32 = 2;
+
+const HEADER_LEN: usize = 20;
+const NODE_RECORD_LEN: usize = 32;
+const EDGE_RECORD_LEN: usize = 5;
+
+// `FatherID`/`MotherID`/`SpouseID` are `Option<i32>`; no person in practice
+// has this id, so it doubles as the "absent" sentinel on disk.
+const NONE_ID: i32 = i32::MIN;
+
+fn encode_optional_id(id: Option<i32>) -> i32 {
+    id.unwrap_or(NONE_ID)
+}
+
+fn decode_optional_id(raw: i32) -> Option<i32> {
+    if raw == NONE_ID {
+        None
+    } else {
+        Some(raw)
+    }
+}
+
+fn relationship_tag(rel: Relationship) -> u8 {
+    match rel {
+        Relationship::Spouse => 0,
+        Relationship::Father => 1,
+        Relationship::Mother => 2,
+    }
+}
+
+fn relationship_from_tag(tag: u8) -> Result<Relationship, Box<Error>> {
+    match tag {
+        0 => Ok(Relationship::Spouse),
+        1 => Ok(Relationship::Father),
+        2 => Ok(Relationship::Mother),
+        _ => Err("invalid relationship tag in index file".into()),
+    }
+}
+
+// Serializes `graph` + `heritage_map` into the on-disk format described above.
+pub(crate) fn build_index(
+    graph: &PersonGraph,
+    heritage_map: &HeritageMap,
+    out_path: &str,
+) -> Result<(), Box<Error>> {
+    let node_count = graph.node_count();
+
+    let mut heritage_by_node: Vec<Option<&Heritage>> = vec![None; node_count];
+    for heritage in heritage_map.values() {
+        heritage_by_node[heritage.node_idx.index()] = Some(heritage);
+    }
+
+    let mut string_table = Vec::new();
+    let mut node_records = Vec::with_capacity(node_count);
+    let mut edge_records: Vec<(u32, u8)> = Vec::new();
+
+    for node_idx_raw in 0..node_count {
+        let node_idx = NodeIndex::<u32>::new(node_idx_raw);
+        let person_id = graph[node_idx];
+
+        let person = heritage_by_node[node_idx_raw].map(|heritage| &heritage.person);
+
+        let father_id = encode_optional_id(person.and_then(|person| person.FatherID));
+        let mother_id = encode_optional_id(person.and_then(|person| person.MotherID));
+        let spouse_id = encode_optional_id(person.and_then(|person| person.SpouseID));
+
+        let name = person.map(|person| person.Person.as_str()).unwrap_or("");
+
+        let name_offset = string_table.len() as u32;
+        let name_len = name.len() as u32;
+        string_table.extend_from_slice(name.as_bytes());
+
+        let edge_offset = edge_records.len() as u32;
+        let mut edge_count = 0u32;
+
+        for edge in graph.edges(node_idx) {
+            edge_records.push((edge.target().index() as u32, relationship_tag(*edge.weight())));
+            edge_count += 1;
+        }
+
+        node_records.push((
+            person_id,
+            father_id,
+            mother_id,
+            spouse_id,
+            name_offset,
+            name_len,
+            edge_offset,
+            edge_count,
+        ));
+    }
+
+    let mut buf = Vec::with_capacity(
+        HEADER_LEN
+            + node_records.len() * NODE_RECORD_LEN
+            + edge_records.len() * EDGE_RECORD_LEN
+            + string_table.len(),
+    );
+
+    buf.extend_from_slice(MAGIC);
+    buf.extend_from_slice(&VERSION.to_le_bytes());
+    buf.extend_from_slice(&(node_records.len() as u32).to_le_bytes());
+    buf.extend_from_slice(&(edge_records.len() as u32).to_le_bytes());
+    buf.extend_from_slice(&(string_table.len() as u32).to_le_bytes());
+
+    for (person_id, father_id, mother_id, spouse_id, name_offset, name_len, edge_offset, edge_count) in
+        &node_records
+    {
+        buf.extend_from_slice(&person_id.to_le_bytes());
+        buf.extend_from_slice(&father_id.to_le_bytes());
+        buf.extend_from_slice(&mother_id.to_le_bytes());
+        buf.extend_from_slice(&spouse_id.to_le_bytes());
+        buf.extend_from_slice(&name_offset.to_le_bytes());
+        buf.extend_from_slice(&name_len.to_le_bytes());
+        buf.extend_from_slice(&edge_offset.to_le_bytes());
+        buf.extend_from_slice(&edge_count.to_le_bytes());
+    }
+
+    for (neighbor, tag) in &edge_records {
+        buf.extend_from_slice(&neighbor.to_le_bytes());
+        buf.push(*tag);
+    }
+
+    buf.extend_from_slice(&string_table);
+
+    fs::write(out_path, buf)?;
+
+    Ok(())
+}
+
+// A loaded index, backed by a memory-mapped file. Node/name/edge lookups read
+// straight out of the mmapped slice instead of allocating per-node structs.
+pub(crate) struct Index {
+    mmap: Mmap,
+    node_count: u32,
+    edge_count: u32,
+}
+
+impl Index {
+    pub(crate) fn open(path: &str) -> Result<Index, Box<Error>> {
+        let file = File::open(path)?;
+        let mmap = unsafe { Mmap::map(&file)? };
+
+        if mmap.len() < HEADER_LEN || &mmap[0..4] != MAGIC {
+            return Err("not a heritage-pathfind index file".into());
+        }
+
+        let version = u32::from_le_bytes(mmap[4..8].try_into().unwrap());
+        if version != VERSION {
+            return Err(format!(
+                "index file was built with format version {}, expected {}; rebuild it with build-index",
+                version, VERSION
+            )
+            .into());
+        }
+
+        let node_count = u32::from_le_bytes(mmap[8..12].try_into().unwrap());
+        let edge_count = u32::from_le_bytes(mmap[12..16].try_into().unwrap());
+        let string_table_len = u32::from_le_bytes(mmap[16..20].try_into().unwrap());
+
+        let expected_len = HEADER_LEN
+            + node_count as usize * NODE_RECORD_LEN
+            + edge_count as usize * EDGE_RECORD_LEN
+            + string_table_len as usize;
+        if mmap.len() != expected_len {
+            return Err(format!(
+                "index file is truncated or corrupted: header implies {} bytes, file is {}",
+                expected_len,
+                mmap.len()
+            )
+            .into());
+        }
+
+        Ok(Index {
+            mmap,
+            node_count,
+            edge_count,
+        })
+    }
+
+    pub(crate) fn node_count(&self) -> u32 {
+        self.node_count
+    }
+
+    fn node_record(&self, node_idx: u32) -> &[u8] {
+        let start = HEADER_LEN + node_idx as usize * NODE_RECORD_LEN;
+        &self.mmap[start..start + NODE_RECORD_LEN]
+    }
+
+    fn string_table_start(&self) -> usize {
+        HEADER_LEN
+            + self.node_count as usize * NODE_RECORD_LEN
+            + self.edge_count as usize * EDGE_RECORD_LEN
+    }
+
+    pub(crate) fn person_id(&self, node_idx: u32) -> i32 {
+        i32::from_le_bytes(self.node_record(node_idx)[0..4].try_into().unwrap())
+    }
+
+    pub(crate) fn father_id(&self, node_idx: u32) -> Option<i32> {
+        let raw = i32::from_le_bytes(self.node_record(node_idx)[4..8].try_into().unwrap());
+        decode_optional_id(raw)
+    }
+
+    pub(crate) fn mother_id(&self, node_idx: u32) -> Option<i32> {
+        let raw = i32::from_le_bytes(self.node_record(node_idx)[8..12].try_into().unwrap());
+        decode_optional_id(raw)
+    }
+
+    pub(crate) fn spouse_id(&self, node_idx: u32) -> Option<i32> {
+        let raw = i32::from_le_bytes(self.node_record(node_idx)[12..16].try_into().unwrap());
+        decode_optional_id(raw)
+    }
+
+    pub(crate) fn name(&self, node_idx: u32) -> &str {
+        let record = self.node_record(node_idx);
+        let name_offset = u32::from_le_bytes(record[16..20].try_into().unwrap()) as usize;
+        let name_len = u32::from_le_bytes(record[20..24].try_into().unwrap()) as usize;
+
+        let start = self.string_table_start() + name_offset;
+        std::str::from_utf8(&self.mmap[start..start + name_len]).unwrap_or("")
+    }
+
+    pub(crate) fn edges(&self, node_idx: u32) -> Result<Vec<(u32, Relationship)>, Box<Error>> {
+        let record = self.node_record(node_idx);
+        let edge_offset = u32::from_le_bytes(record[24..28].try_into().unwrap()) as usize;
+        let edge_count = u32::from_le_bytes(record[28..32].try_into().unwrap()) as usize;
+
+        let edges_start = HEADER_LEN + self.node_count as usize * NODE_RECORD_LEN;
+
+        let mut result = Vec::with_capacity(edge_count);
+        for i in 0..edge_count {
+            let start = edges_start + (edge_offset + i) * EDGE_RECORD_LEN;
+            let neighbor = u32::from_le_bytes(self.mmap[start..start + 4].try_into().unwrap());
+            let tag = self.mmap[start + 4];
+            result.push((neighbor, relationship_from_tag(tag)?));
+        }
+
+        Ok(result)
+    }
+}
+
+// Rebuilds a `PersonGraph` + `HeritageMap` from a loaded index, so the
+// existing pathfinding functions can run against it unchanged.
+pub(crate) fn graph_from_index(index: &Index) -> Result<(PersonGraph, HeritageMap), Box<Error>> {
+    let node_count = index.node_count();
+
+    let mut graph = PersonGraph::with_capacity(node_count as usize, 0);
+    let mut heritage_map = HeritageMap::default();
+    let mut node_indices = Vec::with_capacity(node_count as usize);
+
+    for i in 0..node_count {
+        node_indices.push(graph.add_node(index.person_id(i)));
+    }
+
+    for i in 0..node_count {
+        let person_id = index.person_id(i);
+
+        let person = Person {
+            PersonID: person_id,
+            SpouseID: index.spouse_id(i),
+            FatherID: index.father_id(i),
+            MotherID: index.mother_id(i),
+            Person: index.name(i).to_string(),
+        };
+
+        for (neighbor_idx, relationship) in index.edges(i)? {
+            // Every undirected edge shows up once in each endpoint's adjacency
+            // list; only re-add it from the lower-indexed side so it isn't
+            // duplicated in the rebuilt graph. `Person` fields are already
+            // known directly from the index, not inferred from this edge.
+            if neighbor_idx > i {
+                graph.add_edge(
+                    node_indices[i as usize],
+                    node_indices[neighbor_idx as usize],
+                    relationship,
+                );
+            }
+        }
+
+        heritage_map.insert(
+            person_id,
+            Heritage {
+                person,
+                node_idx: node_indices[i as usize],
+            },
+        );
+    }
+
+    Ok((graph, heritage_map))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{extract_graph_from_csv, get_common_ancestor, get_kinship_term};
+    use crate::revset;
+
+    #[test]
+    fn round_trip() {
+        let mut graph = PersonGraph::default();
+        let mut heritage_map = HeritageMap::default();
+
+        // Person 2 has both a parent (3) and a child (1), so the bug where a
+        // parent node's own FatherID gets mistaken for one of its children's
+        // ids (the undirected graph carries no inherent direction) would
+        // show up here.
+        let child_idx = graph.add_node(1);
+        let middle_idx = graph.add_node(2);
+        let grandparent_idx = graph.add_node(3);
+        graph.add_edge(child_idx, middle_idx, Relationship::Father);
+        graph.add_edge(middle_idx, grandparent_idx, Relationship::Father);
+
+        heritage_map.insert(
+            1,
+            Heritage {
+                person: Person {
+                    PersonID: 1,
+                    SpouseID: None,
+                    FatherID: Some(2),
+                    MotherID: None,
+                    Person: "Child".to_string(),
+                },
+                node_idx: child_idx,
+            },
+        );
+        heritage_map.insert(
+            2,
+            Heritage {
+                person: Person {
+                    PersonID: 2,
+                    SpouseID: None,
+                    FatherID: Some(3),
+                    MotherID: None,
+                    Person: "Parent".to_string(),
+                },
+                node_idx: middle_idx,
+            },
+        );
+        heritage_map.insert(
+            3,
+            Heritage {
+                person: Person {
+                    PersonID: 3,
+                    SpouseID: None,
+                    FatherID: None,
+                    MotherID: None,
+                    Person: "Grandparent".to_string(),
+                },
+                node_idx: grandparent_idx,
+            },
+        );
+
+        let path = std::env::temp_dir().join(format!(
+            "heritage-pathfind-test-{}.idx",
+            std::process::id()
+        ));
+        let path_str = path.to_str().unwrap().to_string();
+
+        build_index(&graph, &heritage_map, &path_str).unwrap();
+
+        let loaded = Index::open(&path_str).unwrap();
+        let (rebuilt_graph, rebuilt_heritage_map) = graph_from_index(&loaded).unwrap();
+
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(rebuilt_graph.node_count(), 3);
+        assert_eq!(rebuilt_graph.edge_count(), 2);
+        assert_eq!(rebuilt_heritage_map[&1].person.FatherID, Some(2));
+        assert_eq!(rebuilt_heritage_map[&2].person.FatherID, Some(3));
+        assert_eq!(rebuilt_heritage_map[&2].person.Person, "Parent");
+        assert_eq!(rebuilt_heritage_map[&3].person.FatherID, None);
+        assert_eq!(rebuilt_heritage_map[&3].person.MotherID, None);
+    }
+
+    // Loads the repo's extended-family CSV fixture both directly and via a
+    // round trip through `build_index`/`Index::open`/`graph_from_index`, and
+    // checks that the MRCA query, kinship term and a revset `ancestors()`
+    // query all agree between the two, i.e. that the index path is safe to
+    // use in place of `--relationship-csv` for these queries.
+    #[test]
+    fn round_trip_preserves_query_results() {
+        const CSV: &str = r#"PersonID;SpouseID;FatherID;MotherID;Person
+100;101;;;F0
+101;100;;;M0
+102;103;100;101;F1
+103;102;;;M1
+104;105;100;101;F2
+105;104;;;M2
+106;108;102;103;C1
+107;;104;105;C2
+108;106;;;SC1
+109;;106;;GC1
+110;;107;;C3"#;
+
+        let (csv_graph, csv_heritage_map) = extract_graph_from_csv(CSV.as_bytes()).unwrap();
+
+        let path = std::env::temp_dir().join(format!(
+            "heritage-pathfind-test-queries-{}.idx",
+            std::process::id()
+        ));
+        let path_str = path.to_str().unwrap().to_string();
+
+        build_index(&csv_graph, &csv_heritage_map, &path_str).unwrap();
+
+        let loaded = Index::open(&path_str).unwrap();
+        let (index_graph, index_heritage_map) = graph_from_index(&loaded).unwrap();
+
+        std::fs::remove_file(&path).unwrap();
+
+        let csv_common = get_common_ancestor(&csv_graph, &csv_heritage_map, 106, 110)
+            .unwrap()
+            .unwrap();
+        let index_common = get_common_ancestor(&index_graph, &index_heritage_map, 106, 110)
+            .unwrap()
+            .unwrap();
+        assert_eq!(csv_common.id, index_common.id);
+
+        let csv_kinship = get_kinship_term(&csv_graph, &csv_heritage_map, 100, 108).unwrap();
+        let index_kinship =
+            get_kinship_term(&index_graph, &index_heritage_map, 100, 108).unwrap();
+        assert_eq!(csv_kinship, index_kinship);
+        assert_eq!(csv_kinship, Some("grandparent-in-law".to_string()));
+
+        let expr = revset::parse("ancestors(106) & ancestors(110)").unwrap();
+        let csv_ancestors = revset::evaluate(&csv_graph, &csv_heritage_map, &expr);
+        let index_ancestors = revset::evaluate(&index_graph, &index_heritage_map, &expr);
+        assert_eq!(csv_ancestors, index_ancestors);
+    }
+
+    // A file with the right magic but an old (pre-FatherID/MotherID/SpouseID)
+    // version number must be rejected outright, not read with the current
+    // (wider) NODE_RECORD_LEN stride.
+    #[test]
+    fn rejects_old_layout_version() {
+        let mut buf = Vec::new();
+        buf.extend_from_slice(MAGIC);
+        buf.extend_from_slice(&1u32.to_le_bytes()); // version 1: 20-byte records
+        buf.extend_from_slice(&1u32.to_le_bytes()); // node_count
+        buf.extend_from_slice(&0u32.to_le_bytes()); // edge_count
+        buf.extend_from_slice(&0u32.to_le_bytes()); // string_table_len
+        buf.extend_from_slice(&[0u8; 20]); // one old-layout (20-byte) node record
+
+        let path = std::env::temp_dir().join(format!(
+            "heritage-pathfind-test-old-version-{}.idx",
+            std::process::id()
+        ));
+        let path_str = path.to_str().unwrap().to_string();
+        std::fs::write(&path, &buf).unwrap();
+
+        let result = Index::open(&path_str);
+
+        std::fs::remove_file(&path).unwrap();
+
+        assert!(result.is_err());
+    }
+
+    // A header claiming more nodes/edges/string bytes than the file actually
+    // holds must be rejected, not read out of bounds.
+    #[test]
+    fn rejects_truncated_file() {
+        let mut buf = Vec::new();
+        buf.extend_from_slice(MAGIC);
+        buf.extend_from_slice(&VERSION.to_le_bytes());
+        buf.extend_from_slice(&1_000_000u32.to_le_bytes()); // node_count (lies)
+        buf.extend_from_slice(&0u32.to_le_bytes());
+        buf.extend_from_slice(&0u32.to_le_bytes());
+
+        let path = std::env::temp_dir().join(format!(
+            "heritage-pathfind-test-truncated-{}.idx",
+            std::process::id()
+        ));
+        let path_str = path.to_str().unwrap().to_string();
+        std::fs::write(&path, &buf).unwrap();
+
+        let result = Index::open(&path_str);
+
+        std::fs::remove_file(&path).unwrap();
+
+        assert!(result.is_err());
+    }
+}
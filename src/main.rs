@@ -8,31 +8,35 @@ use fxhash::FxBuildHasher;
 
 use petgraph::algo::astar;
 use petgraph::graph::{EdgeIndex, Graph, NodeIndex};
+use petgraph::visit::EdgeRef;
 
 use serde::Deserialize;
 
 use structopt::StructOpt;
 
+mod index;
+mod revset;
+
 #[allow(non_snake_case)]
 #[derive(PartialEq, Eq, Debug, Deserialize)]
-struct Person {
-    PersonID: i32,
-    SpouseID: Option<i32>,
-    FatherID: Option<i32>,
-    MotherID: Option<i32>,
+pub(crate) struct Person {
+    pub(crate) PersonID: i32,
+    pub(crate) SpouseID: Option<i32>,
+    pub(crate) FatherID: Option<i32>,
+    pub(crate) MotherID: Option<i32>,
 
     /// Name of the person.
-    Person: String,
+    pub(crate) Person: String,
 }
 
 #[derive(Debug)]
-struct Heritage {
-    person: Person,
-    node_idx: NodeIndex<u32>,
+pub(crate) struct Heritage {
+    pub(crate) person: Person,
+    pub(crate) node_idx: NodeIndex<u32>,
 }
 
 #[derive(Copy, Clone, PartialEq, Eq, Debug)]
-enum Relationship {
+pub(crate) enum Relationship {
     Spouse,
     Father,
     Mother,
@@ -45,12 +49,12 @@ struct PersonRelationship {
     relationship: Option<Relationship>,
 }
 
-type HeritageMap = HashMap<i32, Heritage, FxBuildHasher>;
+pub(crate) type HeritageMap = HashMap<i32, Heritage, FxBuildHasher>;
 
 // Store person id per node, and relationship type as edge information.
 // Undirected to allow for indirect heritage paths.
 // u32 index space, if you have more than 4B nodes change.
-type PersonGraph = Graph<i32, Relationship, petgraph::Undirected, u32>;
+pub(crate) type PersonGraph = Graph<i32, Relationship, petgraph::Undirected, u32>;
 
 impl fmt::Display for PersonRelationship {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
@@ -96,7 +100,7 @@ fn add_graph_edges(graph: &mut PersonGraph, heritage_map: &HeritageMap) {
 
 // Build up graph and companion data structure while parsing csv.
 // Not the most beautiful approach, yet should help avoiding unnecessary copies.
-fn extract_graph_from_csv<R: io::Read>(
+pub(crate) fn extract_graph_from_csv<R: io::Read>(
     rdr: R,
 ) -> Result<(PersonGraph, HeritageMap), Box<Error>> {
     let mut rdr = csv::ReaderBuilder::new().delimiter(b';').from_reader(rdr);
@@ -149,11 +153,460 @@ fn map_edges(
     vec
 }
 
+// Depth (in generations) of every ancestor reachable from `start_id` by walking
+// only `Father`/`Mother` edges upward, keyed by person id. `start_id` itself is
+// depth 0. `predecessor` links back towards `start_id`, so a lineage path can be
+// rebuilt by following it from any reached id.
+struct AncestorNode {
+    depth: u32,
+    predecessor: Option<i32>,
+}
+
+// BFS over parent edges only, level by level, recording the first (and thus
+// shallowest) generation depth at which each ancestor is reached. Mirrors
+// Mercurial's heap-based ancestors iterator, specialized to the two parent
+// edge kinds.
+pub(crate) fn ancestor_depths(
+    graph: &PersonGraph,
+    heritage_map: &HeritageMap,
+    start_id: i32,
+) -> HashMap<i32, AncestorNode, FxBuildHasher> {
+    let mut visited: HashMap<i32, AncestorNode, FxBuildHasher> = HashMap::default();
+    visited.insert(
+        start_id,
+        AncestorNode {
+            depth: 0,
+            predecessor: None,
+        },
+    );
+
+    let mut frontier = vec![start_id];
+    let mut depth = 0;
+
+    while !frontier.is_empty() {
+        depth += 1;
+        let mut next_frontier = Vec::new();
+
+        for id in frontier {
+            let heritage = match heritage_map.get(&id) {
+                Some(heritage) => heritage,
+                None => continue,
+            };
+
+            for edge in graph.edges(heritage.node_idx) {
+                if *edge.weight() != Relationship::Father && *edge.weight() != Relationship::Mother
+                {
+                    continue;
+                }
+
+                // The graph is undirected, so the edge alone can't tell parent from
+                // child; confirm the neighbor is actually this person's parent.
+                let neighbor_id = graph[edge.target()];
+                let is_parent = heritage.person.FatherID == Some(neighbor_id)
+                    || heritage.person.MotherID == Some(neighbor_id);
+
+                if is_parent && !visited.contains_key(&neighbor_id) {
+                    visited.insert(
+                        neighbor_id,
+                        AncestorNode {
+                            depth,
+                            predecessor: Some(id),
+                        },
+                    );
+                    next_frontier.push(neighbor_id);
+                }
+            }
+        }
+
+        frontier = next_frontier;
+    }
+
+    visited
+}
+
+// Rebuilds the lineage path from `start_id` up to `target_id` (an ancestor of
+// `start_id` present in `ancestors`) and labels each hop with its relationship.
+// `map_edges` expects nodes ordered target-first so that, once reversed, the
+// printed path reads start-first and ends at `target_id`.
+fn lineage_path(
+    graph: &PersonGraph,
+    heritage_map: &HeritageMap,
+    ancestors: &HashMap<i32, AncestorNode, FxBuildHasher>,
+    start_id: i32,
+    target_id: i32,
+) -> Result<Vec<PersonRelationship>, Box<Error>> {
+    let mut ids = vec![target_id];
+    let mut current = target_id;
+
+    while current != start_id {
+        current = ancestors[&current]
+            .predecessor
+            .ok_or("broken ancestor chain")?;
+        ids.push(current);
+    }
+
+    let nodes = ids
+        .iter()
+        .map(|id| {
+            heritage_map
+                .get(id)
+                .map(|heritage| heritage.node_idx)
+                .ok_or("invalid person_id")
+        })
+        .collect::<Result<Vec<NodeIndex<u32>>, _>>()?;
+
+    let lookup_name = |person_id| {
+        heritage_map
+            .get(&person_id)
+            .map(|heritage| heritage.person.Person.clone())
+            .ok_or("invalid person_id")
+    };
+
+    let mut rels = Vec::new();
+
+    for (person_id, edge_opt) in map_edges(&nodes, &graph) {
+        rels.push(PersonRelationship {
+            id: person_id,
+            name: lookup_name(person_id)?,
+            relationship: edge_opt
+                .map(|edge| graph.edge_weight(edge).cloned())
+                .unwrap_or(None),
+        });
+    }
+
+    Ok(rels)
+}
+
+#[derive(Debug)]
+pub(crate) struct CommonAncestor {
+    pub(crate) id: i32,
+    name: String,
+    path_from_a: Vec<PersonRelationship>,
+    path_from_b: Vec<PersonRelationship>,
+}
+
+// Finds the most-recent common ancestor of `id_a` and `id_b`, i.e. the common
+// id minimizing generations-from-a plus generations-from-b, ties broken by
+// minimizing the larger of the two. Returns `None` if the two have no ancestor
+// in common. This is a genuinely different query from `get_shortest_path`,
+// which also follows `Spouse` edges and isn't ancestor-aware.
+pub(crate) fn get_common_ancestor(
+    graph: &PersonGraph,
+    heritage_map: &HeritageMap,
+    id_a: i32,
+    id_b: i32,
+) -> Result<Option<CommonAncestor>, Box<Error>> {
+    heritage_map.get(&id_a).ok_or("invalid start id")?;
+    heritage_map.get(&id_b).ok_or("invalid finish id")?;
+
+    let ancestors_a = ancestor_depths(graph, heritage_map, id_a);
+    let ancestors_b = ancestor_depths(graph, heritage_map, id_b);
+
+    let mrca_id = ancestors_a
+        .iter()
+        .filter_map(|(id, node_a)| {
+            ancestors_b
+                .get(id)
+                .map(|node_b| (*id, node_a.depth, node_b.depth))
+        })
+        .min_by_key(|(_, depth_a, depth_b)| (depth_a + depth_b, *depth_a.max(depth_b)))
+        .map(|(id, _, _)| id);
+
+    let mrca_id = match mrca_id {
+        Some(id) => id,
+        None => return Ok(None),
+    };
+
+    let name = heritage_map
+        .get(&mrca_id)
+        .map(|heritage| heritage.person.Person.clone())
+        .ok_or("invalid person_id")?;
+
+    Ok(Some(CommonAncestor {
+        id: mrca_id,
+        name,
+        path_from_a: lineage_path(graph, heritage_map, &ancestors_a, id_a, mrca_id)?,
+        path_from_b: lineage_path(graph, heritage_map, &ancestors_b, id_b, mrca_id)?,
+    }))
+}
+
+fn fmt_common_ancestor(common_ancestor: &CommonAncestor) -> String {
+    format!(
+        "Most recent common ancestor: {}({})\n\nPath from first person:\n{}\n\nPath from second person:\n{}",
+        common_ancestor.name,
+        common_ancestor.id,
+        fmt_person_relationships(&common_ancestor.path_from_a),
+        fmt_person_relationships(&common_ancestor.path_from_b),
+    )
+}
+
+// English ordinal word for cousin degree, e.g. 1 -> "first", 2 -> "second".
+// Falls back to a numeric "Nth" for degrees beyond common usage.
+fn cousin_ordinal(n: u32) -> String {
+    match n {
+        1 => "first".to_string(),
+        2 => "second".to_string(),
+        3 => "third".to_string(),
+        4 => "fourth".to_string(),
+        5 => "fifth".to_string(),
+        6 => "sixth".to_string(),
+        7 => "seventh".to_string(),
+        8 => "eighth".to_string(),
+        9 => "ninth".to_string(),
+        10 => "tenth".to_string(),
+        n => format!("{}th", n),
+    }
+}
+
+// Derives the English kinship term between two people from their generation
+// counts up to their MRCA (`da`, `db`). Returns the term and whether it is a
+// "by marriage"-qualified term (as opposed to a "-in-law" one) when a spouse
+// hop gets reattached by the caller.
+fn kinship_term(da: u32, db: u32) -> (String, bool) {
+    if da == 0 && db == 0 {
+        return ("self".to_string(), false);
+    }
+
+    if da == 0 || db == 0 {
+        let gens = da.max(db);
+        if gens == 1 {
+            return (if da == 0 { "parent" } else { "child" }.to_string(), false);
+        }
+
+        let greats = gens - 2;
+        let base = if da == 0 { "grandparent" } else { "descendant" };
+        return (format!("{}{}", "great-".repeat(greats as usize), base), false);
+    }
+
+    let degree = da.min(db) - 1;
+    let removed = if da > db { da - db } else { db - da };
+
+    if degree == 0 {
+        if removed == 0 {
+            return ("sibling".to_string(), false);
+        }
+
+        // No gender is tracked on `Person`, so "aunt/uncle" and "niece/nephew"
+        // are reported jointly rather than guessed. `da < db` means `id_a` is
+        // the closer generation to the MRCA, i.e. a sibling of `id_b`'s
+        // parent (or grandparent, ...): the aunt/uncle side.
+        let greats = removed - 1;
+        let kin = if da < db { "aunt/uncle" } else { "niece/nephew" };
+        return (format!("{}{}", "great-".repeat(greats as usize), kin), true);
+    }
+
+    let base = format!("{} cousin", cousin_ordinal(degree));
+    let term = match removed {
+        0 => base,
+        1 => format!("{} once removed", base),
+        2 => format!("{} twice removed", base),
+        n => format!("{} {} times removed", base, n),
+    };
+
+    (term, true)
+}
+
+// Computes the English kinship term relating `id_a` to `id_b`, e.g.
+// "grandparent", "first cousin once removed", "child-in-law". Built on top of
+// `get_common_ancestor`: `da`/`db` are the generations from each person up to
+// their MRCA, which is lineal if either is 0 and collateral otherwise. A
+// leading/trailing `Spouse` hop on the general (spouse-inclusive) shortest
+// path is detected and reattached as a "by marriage"/"-in-law" qualifier.
+// Returns `None` if the two share no common ancestor, blood or by marriage.
+pub(crate) fn get_kinship_term(
+    graph: &PersonGraph,
+    heritage_map: &HeritageMap,
+    id_a: i32,
+    id_b: i32,
+) -> Result<Option<String>, Box<Error>> {
+    if id_a == id_b {
+        return Ok(Some("self".to_string()));
+    }
+
+    let path = get_shortest_path(graph, heritage_map, id_a, id_b, None)?;
+
+    if path.len() == 2 && path[0].relationship == Some(Relationship::Spouse) {
+        return Ok(Some("spouse".to_string()));
+    }
+
+    let leading_spouse = path
+        .first()
+        .map(|rel| rel.relationship == Some(Relationship::Spouse))
+        .unwrap_or(false);
+    let trailing_spouse =
+        path.len() >= 2 && path[path.len() - 2].relationship == Some(Relationship::Spouse);
+
+    let blood_id_a = if trailing_spouse {
+        path[path.len() - 2].id
+    } else {
+        id_a
+    };
+    let blood_id_b = if leading_spouse { path[1].id } else { id_b };
+
+    let common = match get_common_ancestor(graph, heritage_map, blood_id_a, blood_id_b)? {
+        Some(common) => common,
+        None => return Ok(None),
+    };
+
+    let da = (common.path_from_a.len() - 1) as u32;
+    let db = (common.path_from_b.len() - 1) as u32;
+
+    let (term, by_marriage) = kinship_term(da, db);
+
+    let term = if !leading_spouse && !trailing_spouse {
+        term
+    } else if by_marriage {
+        format!("{} by marriage", term)
+    } else {
+        format!("{}-in-law", term)
+    };
+
+    Ok(Some(term))
+}
+
+// One BFS level outward from `frontier`, recording predecessors of newly
+// discovered nodes in `own_visited`. Returns the first node also present in
+// `other_visited`, if any, i.e. where the two frontiers meet.
+fn expand_frontier(
+    graph: &PersonGraph,
+    frontier: &mut Vec<NodeIndex<u32>>,
+    own_visited: &mut HashMap<NodeIndex<u32>, Option<NodeIndex<u32>>, FxBuildHasher>,
+    other_visited: &HashMap<NodeIndex<u32>, Option<NodeIndex<u32>>, FxBuildHasher>,
+) -> Option<NodeIndex<u32>> {
+    let current = std::mem::take(frontier);
+    let mut meeting_node = None;
+
+    for node in current {
+        for neighbor in graph.neighbors(node) {
+            if own_visited.contains_key(&neighbor) {
+                continue;
+            }
+
+            own_visited.insert(neighbor, Some(node));
+            frontier.push(neighbor);
+
+            if meeting_node.is_none() && other_visited.contains_key(&neighbor) {
+                meeting_node = Some(neighbor);
+            }
+        }
+    }
+
+    meeting_node
+}
+
+// Walks `visited`'s predecessor chain from `node` back to its BFS root,
+// collecting ids along the way (root last).
+fn predecessor_chain(
+    node: NodeIndex<u32>,
+    visited: &HashMap<NodeIndex<u32>, Option<NodeIndex<u32>>, FxBuildHasher>,
+) -> Vec<NodeIndex<u32>> {
+    let mut chain = Vec::new();
+    let mut current = node;
+
+    while let Some(Some(predecessor)) = visited.get(&current) {
+        chain.push(*predecessor);
+        current = *predecessor;
+    }
+
+    chain
+}
+
+// Bidirectional BFS: expands one frontier from `start_idx` and one from
+// `finish_idx`, alternating expansion of whichever is currently smaller,
+// until a node shows up in both. This explores far fewer nodes than a
+// single-sided search on the typically bushy genealogy graph, and since every
+// edge has unit cost it's still a provably shortest path.
+fn bidirectional_bfs(
+    graph: &PersonGraph,
+    start_idx: NodeIndex<u32>,
+    finish_idx: NodeIndex<u32>,
+) -> Option<Vec<NodeIndex<u32>>> {
+    if start_idx == finish_idx {
+        return Some(vec![start_idx]);
+    }
+
+    let mut visited_from_start: HashMap<NodeIndex<u32>, Option<NodeIndex<u32>>, FxBuildHasher> =
+        HashMap::default();
+    let mut visited_from_finish: HashMap<NodeIndex<u32>, Option<NodeIndex<u32>>, FxBuildHasher> =
+        HashMap::default();
+    visited_from_start.insert(start_idx, None);
+    visited_from_finish.insert(finish_idx, None);
+
+    let mut frontier_from_start = vec![start_idx];
+    let mut frontier_from_finish = vec![finish_idx];
+
+    loop {
+        if frontier_from_start.is_empty() || frontier_from_finish.is_empty() {
+            return None;
+        }
+
+        let meeting_node = if frontier_from_start.len() <= frontier_from_finish.len() {
+            expand_frontier(
+                graph,
+                &mut frontier_from_start,
+                &mut visited_from_start,
+                &visited_from_finish,
+            )
+        } else {
+            expand_frontier(
+                graph,
+                &mut frontier_from_finish,
+                &mut visited_from_finish,
+                &visited_from_start,
+            )
+        };
+
+        if let Some(meeting_node) = meeting_node {
+            let mut nodes = predecessor_chain(meeting_node, &visited_from_start);
+            nodes.reverse();
+            nodes.push(meeting_node);
+            nodes.extend(predecessor_chain(meeting_node, &visited_from_finish));
+
+            return Some(nodes);
+        }
+    }
+}
+
+// Per-`Relationship` edge costs for a weighted shortest path, e.g. to prefer
+// blood lineage over marriage hops. Default mirrors that: parent edges are
+// free to cross, spouse edges are comparatively expensive.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct RelationshipCosts {
+    father: u32,
+    mother: u32,
+    spouse: u32,
+}
+
+impl RelationshipCosts {
+    fn cost(&self, relationship: Relationship) -> u32 {
+        match relationship {
+            Relationship::Father => self.father,
+            Relationship::Mother => self.mother,
+            Relationship::Spouse => self.spouse,
+        }
+    }
+}
+
+impl Default for RelationshipCosts {
+    fn default() -> Self {
+        RelationshipCosts {
+            father: 1,
+            mother: 1,
+            spouse: 10,
+        }
+    }
+}
+
+// Finds the path between `child_id` and `ancestor_id`. With `costs: None`
+// this is the plain unweighted bidirectional BFS; with `costs: Some(..)` it
+// instead runs A* with those per-`Relationship` costs, so a path that
+// prefers blood lineage over marriage hops can be asked for.
 fn get_shortest_path(
     graph: &PersonGraph,
     heritage_map: &HeritageMap,
     child_id: i32,
     ancestor_id: i32,
+    costs: Option<RelationshipCosts>,
 ) -> Result<Vec<PersonRelationship>, Box<Error>> {
     let start_idx = heritage_map
         .get(&child_id)
@@ -165,9 +618,19 @@ fn get_shortest_path(
         .ok_or("invalid finish id")?
         .node_idx;
 
-    let nodes = astar(&graph, start_idx, |e| e == finish_idx, |_| 1, |_| 0)
+    let nodes = match costs {
+        Some(costs) => astar(
+            &graph,
+            start_idx,
+            |node| node == finish_idx,
+            |edge| costs.cost(*edge.weight()),
+            |_| 0,
+        )
         .map(|(_cost, nodes)| nodes)
-        .ok_or("no direct or indirect relationship found")?;
+        .ok_or("no direct or indirect relationship found")?,
+        None => bidirectional_bfs(graph, start_idx, finish_idx)
+            .ok_or("no direct or indirect relationship found")?,
+    };
 
     let lookup_name = |person_id| {
         heritage_map
@@ -201,31 +664,148 @@ fn fmt_person_relationships(rels: &[PersonRelationship]) -> String {
 
 #[derive(Debug, StructOpt)]
 #[structopt(name = "heritage-pathfind", about = "Find person path")]
-struct CmdInput {
-    #[structopt(short = "r", long = "relationship-csv")]
-    csv_path: String,
-    #[structopt(short = "c", long = "child-id")]
-    child_id: i32,
-    #[structopt(short = "a", long = "ancestor-id")]
-    ancestor_id: i32,
+enum CmdInput {
+    /// Find the shortest path between two persons.
+    Path {
+        #[structopt(short = "r", long = "relationship-csv")]
+        csv_path: Option<String>,
+        #[structopt(short = "i", long = "index")]
+        index_path: Option<String>,
+        #[structopt(short = "c", long = "child-id")]
+        child_id: i32,
+        #[structopt(short = "a", long = "ancestor-id")]
+        ancestor_id: i32,
+        /// Prefer blood lineage over marriage hops (Spouse edges cost more).
+        #[structopt(long = "weights")]
+        weights: bool,
+    },
+    /// Find the most-recent common ancestor of two persons.
+    CommonAncestor {
+        #[structopt(short = "r", long = "relationship-csv")]
+        csv_path: Option<String>,
+        #[structopt(short = "i", long = "index")]
+        index_path: Option<String>,
+        #[structopt(short = "x", long = "id-a")]
+        id_a: i32,
+        #[structopt(short = "y", long = "id-b")]
+        id_b: i32,
+    },
+    /// Print the English kinship term relating two persons.
+    Kinship {
+        #[structopt(short = "r", long = "relationship-csv")]
+        csv_path: Option<String>,
+        #[structopt(short = "i", long = "index")]
+        index_path: Option<String>,
+        #[structopt(short = "x", long = "id-a")]
+        id_a: i32,
+        #[structopt(short = "y", long = "id-b")]
+        id_b: i32,
+    },
+    /// Parse a relationship CSV and write it out as a binary, memory-mappable
+    /// index that `--index` can load without reparsing the CSV.
+    BuildIndex {
+        #[structopt(short = "r", long = "relationship-csv")]
+        csv_path: String,
+        #[structopt(short = "o", long = "out")]
+        index_path: String,
+    },
+    /// Evaluate a revset-style set-algebra expression, e.g.
+    /// "ancestors(1) & ancestors(2)" for common ancestors.
+    Query {
+        #[structopt(short = "r", long = "relationship-csv")]
+        csv_path: Option<String>,
+        #[structopt(short = "i", long = "index")]
+        index_path: Option<String>,
+        expression: String,
+    },
+}
+
+// Loads a graph either from a freshly parsed CSV or from a prebuilt `--index`,
+// whichever was given on the command line.
+fn load_graph(
+    csv_path: Option<String>,
+    index_path: Option<String>,
+) -> Result<(PersonGraph, HeritageMap), Box<Error>> {
+    if let Some(index_path) = index_path {
+        let loaded_index = index::Index::open(&index_path)?;
+        return index::graph_from_index(&loaded_index);
+    }
+
+    let csv_path = csv_path.ok_or("either --relationship-csv or --index must be given")?;
+    let csv_file = File::open(csv_path)?;
+    extract_graph_from_csv(csv_file)
 }
 
 fn main() -> Result<(), Box<Error>> {
-    let cmd_input = CmdInput::from_args();
+    match CmdInput::from_args() {
+        CmdInput::Path {
+            csv_path,
+            index_path,
+            child_id,
+            ancestor_id,
+            weights,
+        } => {
+            let (graph, heritage_map) = load_graph(csv_path, index_path)?;
+
+            let costs = if weights {
+                Some(RelationshipCosts::default())
+            } else {
+                None
+            };
 
-    let csv_file = File::open(cmd_input.csv_path)?;
+            let person_relationships =
+                get_shortest_path(&graph, &heritage_map, child_id, ancestor_id, costs)?;
+            println!("{}", fmt_person_relationships(&person_relationships));
+        }
+        CmdInput::CommonAncestor {
+            csv_path,
+            index_path,
+            id_a,
+            id_b,
+        } => {
+            let (graph, heritage_map) = load_graph(csv_path, index_path)?;
 
-    let (graph, heritage_map) = extract_graph_from_csv(csv_file)?;
+            match get_common_ancestor(&graph, &heritage_map, id_a, id_b)? {
+                Some(common_ancestor) => println!("{}", fmt_common_ancestor(&common_ancestor)),
+                None => println!("no common ancestor found"),
+            }
+        }
+        CmdInput::Kinship {
+            csv_path,
+            index_path,
+            id_a,
+            id_b,
+        } => {
+            let (graph, heritage_map) = load_graph(csv_path, index_path)?;
 
-    get_shortest_path(
-        &graph,
-        &heritage_map,
-        cmd_input.child_id,
-        cmd_input.ancestor_id,
-    )
-    .map(|person_relationships| {
-        println!("{}", fmt_person_relationships(&person_relationships));
-    })
+            match get_kinship_term(&graph, &heritage_map, id_a, id_b)? {
+                Some(term) => println!("{}", term),
+                None => println!("no relationship found"),
+            }
+        }
+        CmdInput::BuildIndex {
+            csv_path,
+            index_path,
+        } => {
+            let csv_file = File::open(csv_path)?;
+            let (graph, heritage_map) = extract_graph_from_csv(csv_file)?;
+
+            index::build_index(&graph, &heritage_map, &index_path)?;
+        }
+        CmdInput::Query {
+            csv_path,
+            index_path,
+            expression,
+        } => {
+            let (graph, heritage_map) = load_graph(csv_path, index_path)?;
+
+            let expr = revset::parse(&expression)?;
+            let ids = revset::evaluate(&graph, &heritage_map, &expr);
+            println!("{}", revset::fmt_id_set(&heritage_map, &ids));
+        }
+    }
+
+    Ok(())
 }
 
 #[cfg(test)]
@@ -275,7 +855,7 @@ mod tests {
 
         let (graph, heritage_map) = extract_graph_from_csv(csv).unwrap();
 
-        let path_a = get_shortest_path(&graph, &heritage_map, 1, 5).unwrap();
+        let path_a = get_shortest_path(&graph, &heritage_map, 1, 5, None).unwrap();
 
         let expected_path_a = vec![
             PersonRelationship {
@@ -302,7 +882,7 @@ mod tests {
 
         assert_eq!(path_a, expected_path_a);
 
-        let path_b = get_shortest_path(&graph, &heritage_map, 1, 6).unwrap();
+        let path_b = get_shortest_path(&graph, &heritage_map, 1, 6, None).unwrap();
 
         let expected_path_b = vec![
             PersonRelationship {
@@ -319,4 +899,208 @@ mod tests {
 
         assert_eq!(path_b, expected_path_b);
     }
+
+    // A blood-only chain (200-201-202-203-204, 4 hops) plus a shorter
+    // marriage shortcut (200-201-205-204, 3 hops) between the same two
+    // endpoints, used to exercise `--weights`.
+    const CSV_SHORTCUT: &str = r#"PersonID;SpouseID;FatherID;MotherID;Person
+200;;201;;X
+201;205;202;;A
+202;;203;;B
+203;;204;;C
+204;;;;Y
+205;201;204;;S"#;
+
+    #[test]
+    fn shortest_path_prefers_fewer_hops_without_weights() {
+        let csv = CSV_SHORTCUT.as_bytes();
+
+        let (graph, heritage_map) = extract_graph_from_csv(csv).unwrap();
+
+        let path = get_shortest_path(&graph, &heritage_map, 200, 204, None).unwrap();
+        let ids: Vec<i32> = path.iter().map(|rel| rel.id).collect();
+
+        assert_eq!(ids, vec![204, 205, 201, 200]);
+    }
+
+    #[test]
+    fn shortest_path_prefers_blood_lineage_with_weights() {
+        let csv = CSV_SHORTCUT.as_bytes();
+
+        let (graph, heritage_map) = extract_graph_from_csv(csv).unwrap();
+
+        let path = get_shortest_path(
+            &graph,
+            &heritage_map,
+            200,
+            204,
+            Some(RelationshipCosts::default()),
+        )
+        .unwrap();
+        let ids: Vec<i32> = path.iter().map(|rel| rel.id).collect();
+
+        assert_eq!(ids, vec![204, 203, 202, 201, 200]);
+    }
+
+    #[test]
+    fn common_ancestor() {
+        let csv = CSV.as_bytes();
+
+        let (graph, heritage_map) = extract_graph_from_csv(csv).unwrap();
+
+        let common = get_common_ancestor(&graph, &heritage_map, 1, 2)
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(common.id, 2);
+        assert_eq!(common.name, "F2");
+        assert_eq!(common.path_from_a.last().unwrap().id, 2);
+        assert_eq!(common.path_from_b.last().unwrap().id, 2);
+    }
+
+    #[test]
+    fn common_ancestor_none() {
+        let csv = CSV.as_bytes();
+
+        let (graph, heritage_map) = extract_graph_from_csv(csv).unwrap();
+
+        assert!(get_common_ancestor(&graph, &heritage_map, 5, 6)
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn common_ancestor_invalid_id() {
+        let csv = CSV.as_bytes();
+
+        let (graph, heritage_map) = extract_graph_from_csv(csv).unwrap();
+
+        assert!(get_common_ancestor(&graph, &heritage_map, 9999, 2).is_err());
+        assert!(get_common_ancestor(&graph, &heritage_map, 1, 9999).is_err());
+    }
+
+    #[test]
+    fn kinship_self_and_lineal() {
+        let csv = CSV.as_bytes();
+
+        let (graph, heritage_map) = extract_graph_from_csv(csv).unwrap();
+
+        assert_eq!(
+            get_kinship_term(&graph, &heritage_map, 1, 1).unwrap(),
+            Some("self".to_string())
+        );
+        assert_eq!(
+            get_kinship_term(&graph, &heritage_map, 1, 2).unwrap(),
+            Some("child".to_string())
+        );
+        assert_eq!(
+            get_kinship_term(&graph, &heritage_map, 1, 3).unwrap(),
+            Some("descendant".to_string())
+        );
+        assert_eq!(
+            get_kinship_term(&graph, &heritage_map, 3, 1).unwrap(),
+            Some("grandparent".to_string())
+        );
+    }
+
+    // Extended family used for collateral and by-marriage kinship terms:
+    // [F0] [M0]
+    //     |  |
+    //    [F1]  [S1]    [F2]
+    //        |    |        |
+    //       [C1]-[SC1]   [C2]
+    //        |               |
+    //      [GC1]           [C3]
+    const CSV_EXTENDED_FAMILY: &str = r#"PersonID;SpouseID;FatherID;MotherID;Person
+100;101;;;F0
+101;100;;;M0
+102;103;100;101;F1
+103;102;;;M1
+104;105;100;101;F2
+105;104;;;M2
+106;108;102;103;C1
+107;;104;105;C2
+108;106;;;SC1
+109;;106;;GC1
+110;;107;;C3"#;
+
+    #[test]
+    fn kinship_sibling() {
+        let csv = CSV_EXTENDED_FAMILY.as_bytes();
+
+        let (graph, heritage_map) = extract_graph_from_csv(csv).unwrap();
+
+        assert_eq!(
+            get_kinship_term(&graph, &heritage_map, 102, 104).unwrap(),
+            Some("sibling".to_string())
+        );
+    }
+
+    #[test]
+    fn kinship_cousins() {
+        let csv = CSV_EXTENDED_FAMILY.as_bytes();
+
+        let (graph, heritage_map) = extract_graph_from_csv(csv).unwrap();
+
+        assert_eq!(
+            get_kinship_term(&graph, &heritage_map, 106, 107).unwrap(),
+            Some("first cousin".to_string())
+        );
+        assert_eq!(
+            get_kinship_term(&graph, &heritage_map, 106, 110).unwrap(),
+            Some("first cousin once removed".to_string())
+        );
+    }
+
+    #[test]
+    fn kinship_aunt_uncle_and_nephew_niece() {
+        let csv = CSV_EXTENDED_FAMILY.as_bytes();
+
+        let (graph, heritage_map) = extract_graph_from_csv(csv).unwrap();
+
+        // 104 (F2) is a sibling of 106's parent 102 (F1), i.e. 106's uncle.
+        assert_eq!(
+            get_kinship_term(&graph, &heritage_map, 104, 106).unwrap(),
+            Some("aunt/uncle".to_string())
+        );
+        // Reciprocally, 106 is 104's niece/nephew.
+        assert_eq!(
+            get_kinship_term(&graph, &heritage_map, 106, 104).unwrap(),
+            Some("niece/nephew".to_string())
+        );
+
+        // 109 (GC1) is 106's child, so 104 is one generation further removed
+        // from 109 than from 106: a great-aunt/uncle.
+        assert_eq!(
+            get_kinship_term(&graph, &heritage_map, 104, 109).unwrap(),
+            Some("great-aunt/uncle".to_string())
+        );
+
+        // 108 (SC1) is 106's spouse, so 104's blood relationship to 106
+        // (aunt/uncle) carries a "by marriage" qualifier for 108.
+        assert_eq!(
+            get_kinship_term(&graph, &heritage_map, 104, 108).unwrap(),
+            Some("aunt/uncle by marriage".to_string())
+        );
+    }
+
+    #[test]
+    fn kinship_by_marriage() {
+        let csv = CSV_EXTENDED_FAMILY.as_bytes();
+
+        let (graph, heritage_map) = extract_graph_from_csv(csv).unwrap();
+
+        assert_eq!(
+            get_kinship_term(&graph, &heritage_map, 106, 108).unwrap(),
+            Some("spouse".to_string())
+        );
+        assert_eq!(
+            get_kinship_term(&graph, &heritage_map, 108, 102).unwrap(),
+            Some("child-in-law".to_string())
+        );
+        assert_eq!(
+            get_kinship_term(&graph, &heritage_map, 100, 108).unwrap(),
+            Some("grandparent-in-law".to_string())
+        );
+    }
 } // mod tests
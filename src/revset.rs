@@ -0,0 +1,498 @@
+// A small revset-style set-algebra query language, inspired by Mercurial's
+// revset engine. Primitives (`ancestors`, `descendants`, `parents`,
+// `children`, `spouse`) each evaluate to a set of person ids; `&`, `|` and
+// `-` combine those sets. `ancestors(A) & ancestors(B)` generalizes the
+// single-pair MRCA query into a composable query subsystem.
+
+use std::collections::HashSet;
+use std::error::Error;
+
+use fxhash::FxBuildHasher;
+
+use petgraph::visit::EdgeRef;
+
+use crate::{ancestor_depths, HeritageMap, PersonGraph, Relationship};
+
+type IdSet = HashSet<i32, FxBuildHasher>;
+
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) enum Expr {
+    Ancestors(i32),
+    Descendants(i32),
+    Parents(i32),
+    Children(i32),
+    Spouse(i32),
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+    Diff(Box<Expr>, Box<Expr>),
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+enum Token {
+    Ident(String),
+    Number(i32),
+    LParen,
+    RParen,
+    Amp,
+    Pipe,
+    Minus,
+}
+
+fn tokenize(input: &str) -> Result<Vec<Token>, Box<Error>> {
+    let mut tokens = Vec::new();
+    let mut chars = input.chars().peekable();
+
+    while let Some(&c) = chars.peek() {
+        match c {
+            c if c.is_whitespace() => {
+                chars.next();
+            }
+            '(' => {
+                chars.next();
+                tokens.push(Token::LParen);
+            }
+            ')' => {
+                chars.next();
+                tokens.push(Token::RParen);
+            }
+            '&' => {
+                chars.next();
+                tokens.push(Token::Amp);
+            }
+            '|' => {
+                chars.next();
+                tokens.push(Token::Pipe);
+            }
+            '-' => {
+                chars.next();
+                tokens.push(Token::Minus);
+            }
+            c if c.is_ascii_digit() => {
+                let mut number = String::new();
+                while let Some(&c) = chars.peek() {
+                    if c.is_ascii_digit() {
+                        number.push(c);
+                        chars.next();
+                    } else {
+                        break;
+                    }
+                }
+                tokens.push(Token::Number(number.parse()?));
+            }
+            c if c.is_alphabetic() || c == '_' => {
+                let mut ident = String::new();
+                while let Some(&c) = chars.peek() {
+                    if c.is_alphanumeric() || c == '_' {
+                        ident.push(c);
+                        chars.next();
+                    } else {
+                        break;
+                    }
+                }
+                tokens.push(Token::Ident(ident));
+            }
+            c => return Err(format!("unexpected character '{}' in expression", c).into()),
+        }
+    }
+
+    Ok(tokens)
+}
+
+// Recursive-descent parser. Precedence, loosest to tightest: `|`/`-`
+// (left-associative, same level), `&`, function calls/parens.
+struct Parser<'a> {
+    tokens: &'a [Token],
+    pos: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Option<&Token> {
+        let token = self.tokens.get(self.pos);
+        self.pos += 1;
+        token
+    }
+
+    fn expect(&mut self, expected: &Token) -> Result<(), Box<Error>> {
+        match self.next() {
+            Some(token) if token == expected => Ok(()),
+            Some(token) => Err(format!("expected {:?}, found {:?}", expected, token).into()),
+            None => Err(format!("expected {:?}, found end of expression", expected).into()),
+        }
+    }
+
+    fn parse_or_diff(&mut self) -> Result<Expr, Box<Error>> {
+        let mut left = self.parse_and()?;
+
+        loop {
+            match self.peek() {
+                Some(Token::Pipe) => {
+                    self.next();
+                    let right = self.parse_and()?;
+                    left = Expr::Or(Box::new(left), Box::new(right));
+                }
+                Some(Token::Minus) => {
+                    self.next();
+                    let right = self.parse_and()?;
+                    left = Expr::Diff(Box::new(left), Box::new(right));
+                }
+                _ => break,
+            }
+        }
+
+        Ok(left)
+    }
+
+    fn parse_and(&mut self) -> Result<Expr, Box<Error>> {
+        let mut left = self.parse_primary()?;
+
+        while let Some(Token::Amp) = self.peek() {
+            self.next();
+            let right = self.parse_primary()?;
+            left = Expr::And(Box::new(left), Box::new(right));
+        }
+
+        Ok(left)
+    }
+
+    fn parse_primary(&mut self) -> Result<Expr, Box<Error>> {
+        match self.next().cloned() {
+            Some(Token::LParen) => {
+                let expr = self.parse_or_diff()?;
+                self.expect(&Token::RParen)?;
+                Ok(expr)
+            }
+            Some(Token::Ident(name)) => {
+                self.expect(&Token::LParen)?;
+                let id = match self.next() {
+                    Some(Token::Number(id)) => *id,
+                    other => return Err(format!("expected person id, found {:?}", other).into()),
+                };
+                self.expect(&Token::RParen)?;
+
+                match name.as_str() {
+                    "ancestors" => Ok(Expr::Ancestors(id)),
+                    "descendants" => Ok(Expr::Descendants(id)),
+                    "parents" => Ok(Expr::Parents(id)),
+                    "children" => Ok(Expr::Children(id)),
+                    "spouse" => Ok(Expr::Spouse(id)),
+                    other => Err(format!("unknown function '{}'", other).into()),
+                }
+            }
+            other => Err(format!("expected expression, found {:?}", other).into()),
+        }
+    }
+}
+
+// Parses a revset expression, e.g. "ancestors(1) & ancestors(2)".
+pub(crate) fn parse(input: &str) -> Result<Expr, Box<Error>> {
+    let tokens = tokenize(input)?;
+    let mut parser = Parser { tokens: &tokens, pos: 0 };
+
+    let expr = parser.parse_or_diff()?;
+
+    if parser.pos != tokens.len() {
+        return Err(format!("unexpected trailing input near {:?}", &tokens[parser.pos..]).into());
+    }
+
+    Ok(expr)
+}
+
+// All ids reachable from `start_id` by walking only `Father`/`Mother` edges
+// downward, i.e. the reverse of `ancestor_depths`: a neighbor is included if
+// `start_id`'s side is the neighbor's recorded parent, BFS level by level.
+fn descendant_ids(graph: &PersonGraph, heritage_map: &HeritageMap, start_id: i32) -> IdSet {
+    let mut visited: IdSet = IdSet::default();
+    visited.insert(start_id);
+
+    let mut frontier = vec![start_id];
+
+    while !frontier.is_empty() {
+        let mut next_frontier = Vec::new();
+
+        for id in frontier {
+            let heritage = match heritage_map.get(&id) {
+                Some(heritage) => heritage,
+                None => continue,
+            };
+
+            for edge in graph.edges(heritage.node_idx) {
+                if *edge.weight() != Relationship::Father && *edge.weight() != Relationship::Mother
+                {
+                    continue;
+                }
+
+                let neighbor_id = graph[edge.target()];
+                let neighbor = match heritage_map.get(&neighbor_id) {
+                    Some(neighbor) => neighbor,
+                    None => continue,
+                };
+                let is_child = neighbor.person.FatherID == Some(id)
+                    || neighbor.person.MotherID == Some(id);
+
+                if is_child && !visited.contains(&neighbor_id) {
+                    visited.insert(neighbor_id);
+                    next_frontier.push(neighbor_id);
+                }
+            }
+        }
+
+        frontier = next_frontier;
+    }
+
+    visited.remove(&start_id);
+    visited
+}
+
+// Direct parents of `id` (0, 1 or 2 ids).
+fn parent_ids(graph: &PersonGraph, heritage_map: &HeritageMap, id: i32) -> IdSet {
+    let mut result = IdSet::default();
+
+    let heritage = match heritage_map.get(&id) {
+        Some(heritage) => heritage,
+        None => return result,
+    };
+
+    for edge in graph.edges(heritage.node_idx) {
+        if *edge.weight() != Relationship::Father && *edge.weight() != Relationship::Mother {
+            continue;
+        }
+
+        let neighbor_id = graph[edge.target()];
+        let is_parent =
+            heritage.person.FatherID == Some(neighbor_id) || heritage.person.MotherID == Some(neighbor_id);
+
+        if is_parent {
+            result.insert(neighbor_id);
+        }
+    }
+
+    result
+}
+
+// Direct children of `id`.
+fn children_ids(graph: &PersonGraph, heritage_map: &HeritageMap, id: i32) -> IdSet {
+    let mut result = IdSet::default();
+
+    let heritage = match heritage_map.get(&id) {
+        Some(heritage) => heritage,
+        None => return result,
+    };
+
+    for edge in graph.edges(heritage.node_idx) {
+        if *edge.weight() != Relationship::Father && *edge.weight() != Relationship::Mother {
+            continue;
+        }
+
+        let neighbor_id = graph[edge.target()];
+        let neighbor = match heritage_map.get(&neighbor_id) {
+            Some(neighbor) => neighbor,
+            None => continue,
+        };
+        let is_child =
+            neighbor.person.FatherID == Some(id) || neighbor.person.MotherID == Some(id);
+
+        if is_child {
+            result.insert(neighbor_id);
+        }
+    }
+
+    result
+}
+
+// Spouse of `id`, if any.
+fn spouse_ids(graph: &PersonGraph, heritage_map: &HeritageMap, id: i32) -> IdSet {
+    let mut result = IdSet::default();
+
+    let heritage = match heritage_map.get(&id) {
+        Some(heritage) => heritage,
+        None => return result,
+    };
+
+    for edge in graph.edges(heritage.node_idx) {
+        if *edge.weight() == Relationship::Spouse {
+            result.insert(graph[edge.target()]);
+        }
+    }
+
+    result
+}
+
+// Recursively evaluates a parsed revset `Expr` into the set of matching
+// person ids.
+pub(crate) fn evaluate(graph: &PersonGraph, heritage_map: &HeritageMap, expr: &Expr) -> IdSet {
+    match expr {
+        Expr::Ancestors(id) => {
+            let mut ids: IdSet = ancestor_depths(graph, heritage_map, *id)
+                .keys()
+                .cloned()
+                .collect();
+            ids.remove(id);
+            ids
+        }
+        Expr::Descendants(id) => descendant_ids(graph, heritage_map, *id),
+        Expr::Parents(id) => parent_ids(graph, heritage_map, *id),
+        Expr::Children(id) => children_ids(graph, heritage_map, *id),
+        Expr::Spouse(id) => spouse_ids(graph, heritage_map, *id),
+        Expr::And(left, right) => {
+            let left = evaluate(graph, heritage_map, left);
+            let right = evaluate(graph, heritage_map, right);
+            left.intersection(&right).cloned().collect()
+        }
+        Expr::Or(left, right) => {
+            let left = evaluate(graph, heritage_map, left);
+            let right = evaluate(graph, heritage_map, right);
+            left.union(&right).cloned().collect()
+        }
+        Expr::Diff(left, right) => {
+            let left = evaluate(graph, heritage_map, left);
+            let right = evaluate(graph, heritage_map, right);
+            left.difference(&right).cloned().collect()
+        }
+    }
+}
+
+// Formats a set of ids as sorted "name(id)" lines, matching the style of
+// `fmt_person_relationships`.
+pub(crate) fn fmt_id_set(heritage_map: &HeritageMap, ids: &IdSet) -> String {
+    let mut ids: Vec<i32> = ids.iter().cloned().collect();
+    ids.sort_unstable();
+
+    ids.iter()
+        .map(|id| match heritage_map.get(id) {
+            Some(heritage) => format!("{}({})", heritage.person.Person, id),
+            None => format!("{}", id),
+        })
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::extract_graph_from_csv;
+
+    // [F0] [M0]
+    //     |  |
+    //    [F1]  [S1]    [F2]
+    //        |    |        |
+    //       [C1]-[SC1]   [C2]
+    //        |               |
+    //      [GC1]           [C3]
+    const CSV: &str = r#"PersonID;SpouseID;FatherID;MotherID;Person
+100;101;;;F0
+101;100;;;M0
+102;103;100;101;F1
+103;102;;;M1
+104;105;100;101;F2
+105;104;;;M2
+106;108;102;103;C1
+107;;104;105;C2
+108;106;;;SC1
+109;;106;;GC1
+110;;107;;C3"#;
+
+    #[test]
+    fn parses_primitives_and_operators() {
+        assert_eq!(parse("ancestors(1)").unwrap(), Expr::Ancestors(1));
+        assert_eq!(parse("descendants(1)").unwrap(), Expr::Descendants(1));
+        assert_eq!(parse("parents(1)").unwrap(), Expr::Parents(1));
+        assert_eq!(parse("children(1)").unwrap(), Expr::Children(1));
+        assert_eq!(parse("spouse(1)").unwrap(), Expr::Spouse(1));
+
+        assert_eq!(
+            parse("ancestors(1) & ancestors(2)").unwrap(),
+            Expr::And(
+                Box::new(Expr::Ancestors(1)),
+                Box::new(Expr::Ancestors(2)),
+            )
+        );
+        assert_eq!(
+            parse("children(1) - spouse(2)").unwrap(),
+            Expr::Diff(Box::new(Expr::Children(1)), Box::new(Expr::Spouse(2)))
+        );
+        assert_eq!(
+            parse("(ancestors(1) | ancestors(2)) & parents(3)").unwrap(),
+            Expr::And(
+                Box::new(Expr::Or(
+                    Box::new(Expr::Ancestors(1)),
+                    Box::new(Expr::Ancestors(2)),
+                )),
+                Box::new(Expr::Parents(3)),
+            )
+        );
+    }
+
+    #[test]
+    fn rejects_malformed_input() {
+        assert!(parse("ancestors(1").is_err());
+        assert!(parse("nonsense(1)").is_err());
+        assert!(parse("ancestors(1) $ ancestors(2)").is_err());
+    }
+
+    #[test]
+    fn evaluates_common_ancestors() {
+        let (graph, heritage_map) = extract_graph_from_csv(CSV.as_bytes()).unwrap();
+
+        let expr = parse("ancestors(106) & ancestors(110)").unwrap();
+        let result = evaluate(&graph, &heritage_map, &expr);
+
+        let mut expected: IdSet = IdSet::default();
+        expected.insert(100);
+        expected.insert(101);
+
+        assert_eq!(result, expected);
+    }
+
+    #[test]
+    fn evaluates_descendants_parents_children_spouse() {
+        let (graph, heritage_map) = extract_graph_from_csv(CSV.as_bytes()).unwrap();
+
+        let descendants_100 = evaluate(&graph, &heritage_map, &Expr::Descendants(100));
+        let mut expected: IdSet = IdSet::default();
+        for id in [102, 104, 106, 107, 109, 110] {
+            expected.insert(id);
+        }
+        assert_eq!(descendants_100, expected);
+
+        let parents_106 = evaluate(&graph, &heritage_map, &Expr::Parents(106));
+        let mut expected_parents: IdSet = IdSet::default();
+        expected_parents.insert(102);
+        expected_parents.insert(103);
+        assert_eq!(parents_106, expected_parents);
+
+        let children_100 = evaluate(&graph, &heritage_map, &Expr::Children(100));
+        let mut expected_children: IdSet = IdSet::default();
+        expected_children.insert(102);
+        expected_children.insert(104);
+        assert_eq!(children_100, expected_children);
+
+        let spouse_106 = evaluate(&graph, &heritage_map, &Expr::Spouse(106));
+        let mut expected_spouse: IdSet = IdSet::default();
+        expected_spouse.insert(108);
+        assert_eq!(spouse_106, expected_spouse);
+    }
+
+    #[test]
+    fn evaluates_union_and_diff() {
+        let (graph, heritage_map) = extract_graph_from_csv(CSV.as_bytes()).unwrap();
+
+        let expr = parse("children(100) - spouse(106)").unwrap();
+        let result = evaluate(&graph, &heritage_map, &expr);
+        let mut expected: IdSet = IdSet::default();
+        expected.insert(102);
+        expected.insert(104);
+        assert_eq!(result, expected);
+
+        let expr = parse("parents(106) | parents(107)").unwrap();
+        let result = evaluate(&graph, &heritage_map, &expr);
+        let mut expected: IdSet = IdSet::default();
+        for id in [102, 103, 104, 105] {
+            expected.insert(id);
+        }
+        assert_eq!(result, expected);
+    }
+}